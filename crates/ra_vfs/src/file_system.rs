@@ -0,0 +1,109 @@
+//! Abstracts the disk access `Vfs` needs behind a trait, so that callers can
+//! plug in an in-memory backend for fast, deterministic tests (no temp
+//! dirs), a read-only snapshot backend, or eventually WASM/remote backends
+//! where `std::fs` isn't available. `Vfs` defaults to [`StdFileSystem`],
+//! which is exactly the `std::fs`/`walkdir`/`notify` behavior the crate
+//! always had.
+//!
+//! This only changes where bytes come from; it doesn't change the
+//! `VfsChange` contract downstream consumers rely on.
+//!
+//! `io::Worker` reads and watches through the same `Arc<dyn FileSystem>` for
+//! both its initial bulk load of a root and the re-reads triggered by watch
+//! events, so an in-memory backend is enough to drive a `Vfs` end to end
+//! without touching disk. A backend with no OS-level watch support (or no
+//! OS at all) can return `None` from `watch` and still serve `read`/`walk`.
+use std::{
+    any::Any,
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::Sender;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use relative_path::RelativePathBuf;
+use walkdir::WalkDir;
+
+use crate::RootFilter;
+
+/// A single change reported by [`FileSystem::watch`], with paths relative to
+/// nothing in particular (callers map them back onto a root via
+/// `RootFilter`). Mirrors the subset of `notify::DebouncedEvent` the
+/// `Worker` cares about, so that non-`notify` backends aren't forced to
+/// depend on `notify`'s event type.
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    Create(PathBuf),
+    Write(PathBuf),
+    Remove(PathBuf),
+}
+
+/// Disk access used by `Vfs` to load, enumerate, and watch files.
+pub trait FileSystem: Send + Sync {
+    /// Reads the full contents of `path` as UTF-8.
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// Enumerates the files `filter` accepts under its root, together with
+    /// their contents.
+    fn walk(&self, filter: &RootFilter) -> Vec<(RelativePathBuf, String)>;
+
+    /// Watches `filter`'s root for changes, forwarding them as `WatchEvent`s
+    /// on `sink`. Returns an opaque handle that keeps the watch alive for as
+    /// long as it's held; dropping it must stop the watch. Backends that
+    /// can't watch their storage (e.g. a remote or WASM backend with no
+    /// OS-level notification mechanism) return `None`, and the `Worker`
+    /// simply runs without a live watch for that root.
+    fn watch(&self, filter: &RootFilter, sink: Sender<WatchEvent>) -> Option<Box<dyn Any + Send>>;
+}
+
+/// The default, `std::fs`- and `walkdir`-backed [`FileSystem`], watching via
+/// `notify`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFileSystem;
+
+impl FileSystem for StdFileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn walk(&self, filter: &RootFilter) -> Vec<(RelativePathBuf, String)> {
+        let mut res = Vec::new();
+        for entry in WalkDir::new(&filter.root)
+            .into_iter()
+            .filter_entry(filter.entry_filter())
+            .filter_map(|it| it.ok())
+        {
+            if entry.file_type().is_file() {
+                if let Some(rel_path) = filter.can_contain(entry.path()) {
+                    let text = self.read(entry.path()).unwrap_or_default();
+                    res.push((rel_path, text));
+                }
+            }
+        }
+        res
+    }
+
+    fn watch(&self, filter: &RootFilter, sink: Sender<WatchEvent>) -> Option<Box<dyn Any + Send>> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_millis(100)).ok()?;
+        watcher.watch(&filter.root, RecursiveMode::Recursive).ok()?;
+        thread::spawn(move || {
+            for event in rx {
+                let event = match event {
+                    DebouncedEvent::Create(path) => Some(WatchEvent::Create(path)),
+                    DebouncedEvent::Write(path) => Some(WatchEvent::Write(path)),
+                    DebouncedEvent::Remove(path) => Some(WatchEvent::Remove(path)),
+                    _ => None,
+                };
+                if let Some(event) = event {
+                    if sink.send(event).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Some(Box::new(watcher))
+    }
+}
@@ -0,0 +1,159 @@
+//! The part of the VFS that talks to the actual file system: bulk-loads the
+//! contents of a root in the background and watches it for changes.
+
+use std::{any::Any, sync::Arc, thread};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use relative_path::RelativePathBuf;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    file_system::{FileSystem, WatchEvent},
+    RootFilter, Roots, VfsRoot,
+};
+
+pub(crate) enum Task {
+    AddRoot {
+        root: VfsRoot,
+        filter: Arc<RootFilter>,
+    },
+    RemoveRoot {
+        root: VfsRoot,
+    },
+}
+
+#[derive(Debug)]
+pub enum TaskResult {
+    BulkLoadRoot {
+        root: VfsRoot,
+        files: Vec<(RelativePathBuf, String)>,
+    },
+    AddSingleFile {
+        root: VfsRoot,
+        path: RelativePathBuf,
+        text: String,
+    },
+    ChangeSingleFile {
+        root: VfsRoot,
+        path: RelativePathBuf,
+        text: String,
+    },
+    RemoveSingleFile {
+        root: VfsRoot,
+        path: RelativePathBuf,
+    },
+}
+
+pub(crate) struct Worker {
+    worker_thread: Option<thread::JoinHandle<()>>,
+    inp: Sender<Task>,
+    out: Receiver<TaskResult>,
+}
+
+impl Worker {
+    /// `fs` is also used for the bulk directory walk each `Task::AddRoot`
+    /// kicks off and for the re-reads a watch event triggers, so an
+    /// in-memory `FileSystem` is enough to run the whole `Vfs` — including
+    /// this background half — without touching disk.
+    pub(crate) fn start(_roots: Arc<Roots>, fs: Arc<dyn FileSystem>) -> Worker {
+        let (inp_sender, inp_receiver) = unbounded::<Task>();
+        let (out_sender, out_receiver) = unbounded::<TaskResult>();
+        let worker_thread = thread::spawn(move || {
+            // Keeping each root's watch handle alive keeps its backing
+            // watch (e.g. the `notify` background thread and the inotify
+            // handle it holds) alive; dropping it on `Task::RemoveRoot`
+            // tears the watch down instead of leaking it for the lifetime
+            // of the process.
+            let mut watchers: FxHashMap<VfsRoot, Box<dyn Any + Send>> = FxHashMap::default();
+            for task in inp_receiver {
+                match task {
+                    Task::AddRoot { root, filter } => {
+                        let files = fs.walk(&filter);
+                        if out_sender
+                            .send(TaskResult::BulkLoadRoot { root, files })
+                            .is_err()
+                        {
+                            break;
+                        }
+                        if let Some(watcher) =
+                            watch_root(root, filter.clone(), fs.clone(), out_sender.clone())
+                        {
+                            watchers.insert(root, watcher);
+                        }
+                    }
+                    Task::RemoveRoot { root } => {
+                        watchers.remove(&root);
+                    }
+                }
+            }
+        });
+        Worker {
+            worker_thread: Some(worker_thread),
+            inp: inp_sender,
+            out: out_receiver,
+        }
+    }
+
+    pub(crate) fn sender(&self) -> &Sender<Task> {
+        &self.inp
+    }
+
+    pub(crate) fn receiver(&self) -> &Receiver<TaskResult> {
+        &self.out
+    }
+
+    pub(crate) fn shutdown(mut self) -> thread::Result<()> {
+        drop(self.inp);
+        if let Some(worker_thread) = self.worker_thread.take() {
+            worker_thread.join()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Starts a watch on `filter`'s root via `fs.watch`, forwarding its
+/// `WatchEvent`s as single-file `TaskResult`s on a dedicated thread,
+/// re-reading changed files through `fs` rather than `std::fs` directly.
+/// The returned handle must be kept alive for the watch to stay active;
+/// dropping it (e.g. when handling `Task::RemoveRoot`) releases the
+/// underlying watch. `None` if `fs` has no watch support for this root
+/// (e.g. a backend with no OS-level notification mechanism).
+fn watch_root(
+    root: VfsRoot,
+    filter: Arc<RootFilter>,
+    fs: Arc<dyn FileSystem>,
+    out: Sender<TaskResult>,
+) -> Option<Box<dyn Any + Send>> {
+    let (tx, rx) = unbounded();
+    let handle = fs.watch(&filter, tx)?;
+    thread::spawn(move || {
+        for event in rx {
+            // Re-check the filter here, as `FileSystem::walk` does for the
+            // initial scan: a raw watch fires for every path under the root
+            // (`.git/index`, ...), not just the ones this root's `RootFilter`
+            // actually wants tracked. Only the changed path itself is
+            // checked: unlike the walk, excluded directories and nested roots
+            // above it are not pruned, so e.g. a `.rs` file under `target/`
+            // is still reported.
+            let result = match event {
+                WatchEvent::Create(path) | WatchEvent::Write(path) => {
+                    filter.can_contain(&path).map(|rel_path| TaskResult::ChangeSingleFile {
+                        root,
+                        path: rel_path,
+                        text: fs.read(&path).unwrap_or_default(),
+                    })
+                }
+                WatchEvent::Remove(path) => filter
+                    .can_contain(&path)
+                    .map(|rel_path| TaskResult::RemoveSingleFile { root, path: rel_path }),
+            };
+            if let Some(result) = result {
+                if out.send(result).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    Some(handle)
+}
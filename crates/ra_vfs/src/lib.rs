@@ -7,18 +7,20 @@
 //!
 //! It is also responsible for watching the disk for changes, and for merging
 //! editor state (modified, unsaved files) with disk state.
-//! TODO: Some LSP clients support watching the disk, so this crate should
-//! to support custom watcher events (related to https://github.com/rust-analyzer/rust-analyzer/issues/131)
+//!
+//! Some LSP clients are able to watch the disk themselves and report changes
+//! via `workspace/didChangeWatchedFiles`; for those clients, `Vfs::notify_changed`
+//! lets the server feed that data in directly instead of (or alongside) the
+//! internal `notify`-based `Worker` (see rust-analyzer/rust-analyzer#131).
 //!
 //! VFS is based on a concept of roots: a set of directories on the file system
 //! which are watched for changes. Typically, there will be a root for each
 //! Cargo package.
+mod file_system;
 mod io;
 
 use std::{
-    cmp::Reverse,
-    fmt, fs, mem,
-    ops::{Deref, DerefMut},
+    fmt, mem,
     path::{Path, PathBuf},
     sync::Arc,
     thread,
@@ -30,23 +32,102 @@ use relative_path::{Component, RelativePath, RelativePathBuf};
 use rustc_hash::{FxHashMap, FxHashSet};
 use walkdir::DirEntry;
 
-pub use crate::io::TaskResult as VfsTask;
+pub use crate::{
+    file_system::{FileSystem, StdFileSystem, WatchEvent},
+    io::TaskResult as VfsTask,
+};
 use io::{TaskResult, Worker};
 
+/// The kind of change reported by an external (e.g. LSP client-side) file
+/// watcher, as fed into [`Vfs::notify_changed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A predicate deciding whether a path belongs in a root, plus the file
+/// extensions and directory names to use when no custom predicate is given.
+///
+/// Passed to [`Vfs::with_config`] to support projects that keep generated
+/// `.rs` files under `target`, use non-standard source layouts, or want to
+/// index additional file types (e.g. `build.rs` generators or `.toml`).
+pub struct RootConfig {
+    pub include_extensions: Vec<String>,
+    pub excluded_dirs: Vec<PathBuf>,
+    /// Like `excluded_dirs`, but only excluded at the root of a project
+    /// (depth 0), not at every depth. The default config uses this for
+    /// `target`, which is only ever a build-output dir at the top level;
+    /// a crate's own nested `src/target/` should still be walked. Put a
+    /// name in `excluded_dirs` instead if it should be excluded everywhere.
+    pub root_only_excluded_dirs: Vec<PathBuf>,
+    pub filter: Option<Arc<dyn Fn(&Path, &RelativePath) -> bool + Send + Sync>>,
+}
+
+impl Default for RootConfig {
+    fn default() -> RootConfig {
+        RootConfig {
+            include_extensions: vec!["rs".to_string()],
+            excluded_dirs: vec![".git", "node_modules"]
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            root_only_excluded_dirs: vec![PathBuf::from("target")],
+            filter: None,
+        }
+    }
+}
+
+impl RootConfig {
+    fn build_filter(&self) -> Arc<dyn Fn(&Path, &RelativePath) -> bool + Send + Sync> {
+        if let Some(filter) = &self.filter {
+            return filter.clone();
+        }
+        let include_extensions = self.include_extensions.clone();
+        let excluded_dirs = self.excluded_dirs.clone();
+        let root_only_excluded_dirs = self.root_only_excluded_dirs.clone();
+        Arc::new(move |path: &Path, rel_path: &RelativePath| {
+            if path.is_dir() {
+                for (i, c) in rel_path.components().enumerate() {
+                    if let Component::Normal(c) = c {
+                        if excluded_dirs.iter().any(|it| it.as_os_str() == c) {
+                            return false;
+                        }
+                        if i == 0 && root_only_excluded_dirs.iter().any(|it| it.as_os_str() == c) {
+                            return false;
+                        }
+                    }
+                }
+                true
+            } else {
+                match rel_path.extension() {
+                    Some(ext) => include_extensions.iter().any(|it| it == ext),
+                    None => false,
+                }
+            }
+        })
+    }
+}
+
 /// `RootFilter` is a predicate that checks if a file can belong to a root. If
 /// several filters match a file (nested dirs), the most nested one wins.
 pub(crate) struct RootFilter {
     root: PathBuf,
-    filter: fn(&Path, &RelativePath) -> bool,
-    excluded_dirs: Vec<PathBuf>,
+    filter: Arc<dyn Fn(&Path, &RelativePath) -> bool + Send + Sync>,
+    nested_roots: Vec<PathBuf>,
 }
 
 impl RootFilter {
-    fn new(root: PathBuf, excluded_dirs: Vec<PathBuf>) -> RootFilter {
+    fn new(
+        root: PathBuf,
+        nested_roots: Vec<PathBuf>,
+        filter: Arc<dyn Fn(&Path, &RelativePath) -> bool + Send + Sync>,
+    ) -> RootFilter {
         RootFilter {
             root,
-            filter: default_filter,
-            excluded_dirs,
+            filter,
+            nested_roots,
         }
     }
     /// Check if this root can contain `path`. NB: even if this returns
@@ -62,7 +143,7 @@ impl RootFilter {
 
     pub(crate) fn entry_filter<'a>(&'a self) -> impl FnMut(&DirEntry) -> bool + 'a {
         move |entry: &DirEntry| {
-            if entry.file_type().is_dir() && self.excluded_dirs.iter().any(|it| it == entry.path())
+            if entry.file_type().is_dir() && self.nested_roots.iter().any(|it| it == entry.path())
             {
                 // do not walk nested roots
                 false
@@ -73,22 +154,6 @@ impl RootFilter {
     }
 }
 
-pub(crate) fn default_filter(path: &Path, rel_path: &RelativePath) -> bool {
-    if path.is_dir() {
-        for (i, c) in rel_path.components().enumerate() {
-            if let Component::Normal(c) = c {
-                // TODO hardcoded for now
-                if (i == 0 && c == "target") || c == ".git" || c == "node_modules" {
-                    return false;
-                }
-            }
-        }
-        true
-    } else {
-        rel_path.extension() == Some("rs")
-    }
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct VfsRoot(pub RawId);
 impl_arena_id!(VfsRoot);
@@ -104,45 +169,102 @@ struct VfsFileData {
     text: Arc<String>,
 }
 
+/// Tracks the set of roots and their nesting relationships.
+///
+/// Unlike `files: Arena<VfsFile, _>`, roots come and go at runtime (editors
+/// open and close workspace folders), so `Roots` keeps its own map instead of
+/// an append-only `Arena` and recomputes nesting on every `insert`/`remove`.
+#[derive(Clone)]
 pub(crate) struct Roots {
-    roots: Arena<VfsRoot, Arc<RootFilter>>,
+    roots: FxHashMap<VfsRoot, Arc<RootFilter>>,
+    filter: Arc<dyn Fn(&Path, &RelativePath) -> bool + Send + Sync>,
+    next_id: u32,
 }
 
 impl Roots {
-    pub(crate) fn new(mut paths: Vec<PathBuf>) -> Roots {
-        let mut roots = Arena::default();
-        // A hack to make nesting work.
-        paths.sort_by_key(|it| Reverse(it.as_os_str().len()));
-        for (i, path) in paths.iter().enumerate() {
-            let nested_roots = paths[..i]
-                .iter()
-                .filter(|it| it.starts_with(path))
-                .map(|it| it.clone())
-                .collect::<Vec<_>>();
+    pub(crate) fn new(
+        paths: Vec<PathBuf>,
+        filter: Arc<dyn Fn(&Path, &RelativePath) -> bool + Send + Sync>,
+    ) -> Roots {
+        let mut res = Roots {
+            roots: FxHashMap::default(),
+            filter,
+            next_id: 0,
+        };
+        for path in paths {
+            res.insert(path);
+        }
+        res
+    }
 
-            let root_filter = Arc::new(RootFilter::new(path.clone(), nested_roots));
+    /// Registers a new root, recomputing nesting for all existing roots, and
+    /// returns its id.
+    pub(crate) fn insert(&mut self, path: PathBuf) -> VfsRoot {
+        let root = VfsRoot(RawId::from(self.next_id));
+        self.next_id += 1;
+        let root_filter = Arc::new(RootFilter::new(path, Vec::new(), self.filter.clone()));
+        self.roots.insert(root, root_filter);
+        self.recompute_nesting();
+        root
+    }
 
-            roots.alloc(root_filter.clone());
+    /// Drops a root, recomputing nesting for the remaining ones.
+    pub(crate) fn remove(&mut self, root: VfsRoot) {
+        self.roots.remove(&root);
+        self.recompute_nesting();
+    }
+
+    fn recompute_nesting(&mut self) {
+        let paths = self
+            .roots
+            .values()
+            .map(|it| it.root.clone())
+            .collect::<Vec<_>>();
+        for root_filter in self.roots.values_mut() {
+            let nested_roots = paths
+                .iter()
+                .filter(|it| it.starts_with(&root_filter.root) && *it != &root_filter.root)
+                .cloned()
+                .collect::<Vec<_>>();
+            *root_filter = Arc::new(RootFilter::new(
+                root_filter.root.clone(),
+                nested_roots,
+                self.filter.clone(),
+            ));
         }
-        Roots { roots }
     }
+
     pub(crate) fn find(&self, path: &Path) -> Option<(VfsRoot, RelativePathBuf)> {
+        // Several roots can contain `path` when they are nested; the most
+        // nested one (the longest root path) wins.
         self.roots
             .iter()
-            .find_map(|(root, data)| data.can_contain(path).map(|it| (root, it)))
+            .filter_map(|(&root, data)| data.can_contain(path).map(|it| (root, data, it)))
+            .max_by_key(|(_, data, _)| data.root.as_os_str().len())
+            .map(|(root, _, it)| (root, it))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (VfsRoot, &Arc<RootFilter>)> + '_ {
+        self.roots.iter().map(|(&root, filter)| (root, filter))
+    }
+
+    pub(crate) fn filter(&self, root: VfsRoot) -> Arc<RootFilter> {
+        self.roots[&root].clone()
     }
-}
 
-impl Deref for Roots {
-    type Target = Arena<VfsRoot, Arc<RootFilter>>;
-    fn deref(&self) -> &Self::Target {
-        &self.roots
+    /// Like indexing, but `None` instead of a panic once `root` has been
+    /// dropped by `remove`. `VfsFile`s can outlive the root they belonged
+    /// to (a caller may still be holding one when its root is removed), so
+    /// callers that can observe such a file need this instead of `Index`.
+    pub(crate) fn get(&self, root: VfsRoot) -> Option<&RootFilter> {
+        self.roots.get(&root).map(|it| it.as_ref())
     }
 }
 
-impl DerefMut for Roots {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.roots
+impl std::ops::Index<VfsRoot> for Roots {
+    type Output = RootFilter;
+    fn index(&self, root: VfsRoot) -> &RootFilter {
+        &self.roots[&root]
     }
 }
 
@@ -150,8 +272,20 @@ pub struct Vfs {
     roots: Arc<Roots>,
     files: Arena<VfsFile, VfsFileData>,
     root2files: FxHashMap<VfsRoot, FxHashSet<VfsFile>>,
+    /// O(1) index from `(root, path)` to the file it holds, kept in sync by
+    /// `add_file`/`remove_file`. `find_file` is hit on every overlay change,
+    /// watcher event and `path2file`/`load`, so it must not scan `root2files`.
+    file_by_path: FxHashMap<(VfsRoot, RelativePathBuf), VfsFile>,
+    /// Ids reclaimed by `remove_file` and `remove_root`, reused by `add_file`
+    /// before falling back to allocating a fresh slot in `files`. Safe without
+    /// a generation counter because consumers apply `VfsChange`s in emission
+    /// order, so the `RemoveFile` for an id (or the `RemoveRoot` of the root
+    /// it belonged to) is always processed before the reused id's `AddFile`
+    /// shows up.
+    free_list: Vec<VfsFile>,
     pending_changes: Vec<VfsChange>,
     worker: Worker,
+    fs: Arc<dyn FileSystem>,
 }
 
 impl fmt::Debug for Vfs {
@@ -162,8 +296,26 @@ impl fmt::Debug for Vfs {
 
 impl Vfs {
     pub fn new(roots: Vec<PathBuf>) -> (Vfs, Vec<VfsRoot>) {
-        let roots = Arc::new(Roots::new(roots));
-        let worker = io::Worker::start(roots.clone());
+        Vfs::with_config(roots, RootConfig::default())
+    }
+
+    /// Like [`Vfs::new`], but with a [`RootConfig`] controlling which files
+    /// and directories each root's watcher and loader consider.
+    pub fn with_config(roots: Vec<PathBuf>, config: RootConfig) -> (Vfs, Vec<VfsRoot>) {
+        Vfs::with_file_system(roots, config, Arc::new(StdFileSystem))
+    }
+
+    /// Like [`Vfs::with_config`], but with a custom [`FileSystem`] in place
+    /// of the default `std::fs`-backed one, e.g. an in-memory backend for
+    /// deterministic tests.
+    pub fn with_file_system(
+        roots: Vec<PathBuf>,
+        config: RootConfig,
+        fs: Arc<dyn FileSystem>,
+    ) -> (Vfs, Vec<VfsRoot>) {
+        let filter = config.build_filter();
+        let roots = Arc::new(Roots::new(roots, filter));
+        let worker = io::Worker::start(roots.clone(), fs.clone());
         let mut root2files = FxHashMap::default();
 
         for (root, filter) in roots.iter() {
@@ -180,15 +332,23 @@ impl Vfs {
             roots,
             files: Arena::default(),
             root2files,
+            file_by_path: FxHashMap::default(),
+            free_list: Vec::new(),
             worker,
+            fs,
             pending_changes: Vec::new(),
         };
         let vfs_roots = res.roots.iter().map(|(id, _)| id).collect();
         (res, vfs_roots)
     }
 
-    pub fn root2path(&self, root: VfsRoot) -> PathBuf {
-        self.roots[root].root.clone()
+    /// Breaking change: this used to return a bare `PathBuf` and panic if
+    /// `root` had been removed; it now returns `None` once `root` has been
+    /// dropped by `remove_root`, mirroring `file2path`'s handling of the
+    /// same race. Callers that destructured the old return type directly
+    /// need to handle the `None` case instead.
+    pub fn root2path(&self, root: VfsRoot) -> Option<PathBuf> {
+        self.roots.get(root).map(|root_filter| root_filter.root.clone())
     }
 
     pub fn path2file(&self, path: &Path) -> Option<VfsFile> {
@@ -200,8 +360,14 @@ impl Vfs {
 
     pub fn file2path(&self, file: VfsFile) -> PathBuf {
         let rel_path = &self.files[file].path;
-        let root_path = &self.roots[self.files[file].root].root;
-        rel_path.to_path(root_path)
+        // The file's root may have been dropped by `remove_root` while this
+        // `VfsFile` was still held by a caller; fall back to the bare
+        // relative path (itself blanked out on removal) rather than
+        // indexing a root that is no longer there.
+        match self.roots.get(self.files[file].root) {
+            Some(root_filter) => rel_path.to_path(&root_filter.root),
+            None => rel_path.to_path(""),
+        }
     }
 
     pub fn file_for_path(&self, path: &Path) -> Option<VfsFile> {
@@ -216,7 +382,7 @@ impl Vfs {
             return if let Some(file) = file {
                 Some(file)
             } else {
-                let text = fs::read_to_string(path).unwrap_or_default();
+                let text = self.fs.read(path).unwrap_or_default();
                 let text = Arc::new(text);
                 let file = self.add_file(root, rel_path.clone(), Arc::clone(&text), false);
                 let change = VfsChange::AddFile {
@@ -232,6 +398,38 @@ impl Vfs {
         None
     }
 
+    /// Feed a watched-file event reported by an external watcher (for example
+    /// an LSP client that advertises the `workspace/didChangeWatchedFiles`
+    /// capability) into the VFS.
+    ///
+    /// This routes to the same `do_add_file` / `do_change_file` /
+    /// `do_remove_file` paths that `handle_task` uses for `TaskResult`,
+    /// re-reading the file through the `Vfs`'s [`FileSystem`] for
+    /// `Created`/`Modified`. Callers that
+    /// rely exclusively on client-side watching can use this instead of the
+    /// internal `notify`-based `Worker`.
+    pub fn notify_changed(&mut self, path: &Path, kind: ChangeKind) {
+        let (root, rel_path, file) = match self.find_root(path) {
+            Some(it) => it,
+            None => return,
+        };
+        match kind {
+            ChangeKind::Created | ChangeKind::Modified => {
+                let text = self.fs.read(path).unwrap_or_default();
+                if let Some(file) = file {
+                    self.do_change_file(file, text, false);
+                } else {
+                    self.do_add_file(root, rel_path, text, false);
+                }
+            }
+            ChangeKind::Removed => {
+                if let Some(file) = file {
+                    self.do_remove_file(root, rel_path, file, false);
+                }
+            }
+        }
+    }
+
     pub fn task_receiver(&self) -> &Receiver<io::TaskResult> {
         self.worker.receiver()
     }
@@ -239,10 +437,18 @@ impl Vfs {
     pub fn handle_task(&mut self, task: io::TaskResult) {
         match task {
             TaskResult::BulkLoadRoot { root, files } => {
+                // The root may have been dropped by `remove_root` while this
+                // bulk load was still in flight on the `Worker` thread; treat
+                // that as a no-op rather than indexing a root that's gone,
+                // mirroring the `Roots::get`/`file2path` fallback above.
+                let cur_root_files = match self.root2files.get(&root) {
+                    Some(files) => files,
+                    None => return,
+                };
                 let mut cur_files = Vec::new();
                 // While we were scanning the root in the backgound, a file might have
                 // been open in the editor, so we need to account for that.
-                let exising = self.root2files[&root]
+                let exising = cur_root_files
                     .iter()
                     .map(|&file| (self.files[file].path.clone(), file))
                     .collect::<FxHashMap<_, _>>();
@@ -264,11 +470,16 @@ impl Vfs {
                 self.pending_changes.push(change);
             }
             TaskResult::AddSingleFile { root, path, text } => {
-                if self.find_file(root, &path).is_none() {
+                // Same race as above: a watcher-originated event for a root
+                // `remove_root` already dropped is a no-op, not a panic.
+                if self.root2files.contains_key(&root) && self.find_file(root, &path).is_none() {
                     self.do_add_file(root, path, text, false);
                 }
             }
             TaskResult::ChangeSingleFile { root, path, text } => {
+                if !self.root2files.contains_key(&root) {
+                    return;
+                }
                 if let Some(file) = self.find_file(root, &path) {
                     self.do_change_file(file, text, false);
                 } else {
@@ -326,6 +537,40 @@ impl Vfs {
             .push(VfsChange::RemoveFile { root, path, file });
     }
 
+    /// Registers a new root (e.g. a Cargo package discovered after startup),
+    /// recomputing nesting for the existing roots and kicking off a bulk
+    /// load on the `Worker`. The files that bulk load finds are reported
+    /// later, via a `VfsChange::AddRoot` returned from `commit_changes`.
+    pub fn add_root(&mut self, path: PathBuf) -> VfsRoot {
+        let root = Arc::make_mut(&mut self.roots).insert(path);
+        self.root2files.insert(root, Default::default());
+        let filter = self.roots.filter(root);
+        self.worker
+            .sender()
+            .send(io::Task::AddRoot { root, filter })
+            .unwrap();
+        root
+    }
+
+    /// Drops a root (e.g. a workspace folder the editor closed), tearing
+    /// down its files and the corresponding watch on the `Worker`.
+    pub fn remove_root(&mut self, root: VfsRoot) {
+        if let Some(files) = self.root2files.remove(&root) {
+            for file in files {
+                let path = mem::replace(&mut self.files[file].path, Default::default());
+                self.files[file].text = Default::default();
+                self.file_by_path.remove(&(root, path));
+                self.free_list.push(file);
+            }
+        }
+        Arc::make_mut(&mut self.roots).remove(root);
+        self.worker
+            .sender()
+            .send(io::Task::RemoveRoot { root })
+            .unwrap();
+        self.pending_changes.push(VfsChange::RemoveRoot { root });
+    }
+
     pub fn add_file_overlay(&mut self, path: &Path, text: String) -> Option<VfsFile> {
         if let Some((root, rel_path, file)) = self.find_root(path) {
             if let Some(file) = file {
@@ -350,7 +595,7 @@ impl Vfs {
         if let Some((root, path, file)) = self.find_root(path) {
             let file = file.expect("can't remove a file which wasn't added");
             let full_path = path.to_path(&self.roots[root].root);
-            if let Ok(text) = fs::read_to_string(&full_path) {
+            if let Ok(text) = self.fs.read(&full_path) {
                 self.do_change_file(file, text, true);
             } else {
                 self.do_remove_file(root, path, file, true);
@@ -379,12 +624,19 @@ impl Vfs {
     ) -> VfsFile {
         let data = VfsFileData {
             root,
-            path,
+            path: path.clone(),
             text,
             is_overlayed,
         };
-        let file = self.files.alloc(data);
+        let file = match self.free_list.pop() {
+            Some(file) => {
+                self.files[file] = data;
+                file
+            }
+            None => self.files.alloc(data),
+        };
         self.root2files.get_mut(&root).unwrap().insert(file);
+        self.file_by_path.insert((root, path), file);
         file
     }
 
@@ -395,12 +647,13 @@ impl Vfs {
     }
 
     fn remove_file(&mut self, file: VfsFile) {
-        //FIXME: use arena with removal
-        self.files[file].text = Default::default();
-        self.files[file].path = Default::default();
         let root = self.files[file].root;
+        let path = mem::replace(&mut self.files[file].path, Default::default());
+        self.files[file].text = Default::default();
         let removed = self.root2files.get_mut(&root).unwrap().remove(&file);
         assert!(removed);
+        self.file_by_path.remove(&(root, path));
+        self.free_list.push(file);
     }
 
     fn find_root(&self, path: &Path) -> Option<(VfsRoot, RelativePathBuf, Option<VfsFile>)> {
@@ -410,10 +663,9 @@ impl Vfs {
     }
 
     fn find_file(&self, root: VfsRoot, path: &RelativePath) -> Option<VfsFile> {
-        self.root2files[&root]
-            .iter()
-            .map(|&it| it)
-            .find(|&file| self.files[file].path == path)
+        self.file_by_path
+            .get(&(root, path.to_relative_path_buf()))
+            .copied()
     }
 }
 
@@ -423,6 +675,9 @@ pub enum VfsChange {
         root: VfsRoot,
         files: Vec<(VfsFile, RelativePathBuf, Arc<String>)>,
     },
+    RemoveRoot {
+        root: VfsRoot,
+    },
     AddFile {
         root: VfsRoot,
         file: VfsFile,
@@ -439,3 +694,146 @@ pub enum VfsChange {
         text: Arc<String>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// An in-memory [`FileSystem`] backed by a map from absolute path to
+    /// contents, so tests can drive a [`Vfs`] deterministically without
+    /// touching disk.
+    #[derive(Default)]
+    struct FakeFileSystem {
+        files: Mutex<FxHashMap<PathBuf, String>>,
+    }
+
+    impl FileSystem for FakeFileSystem {
+        fn read(&self, path: &Path) -> std::io::Result<String> {
+            self.files.lock().unwrap().get(path).cloned().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "no such file")
+            })
+        }
+
+        fn walk(&self, filter: &RootFilter) -> Vec<(RelativePathBuf, String)> {
+            self.files
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(path, text)| {
+                    filter.can_contain(path).map(|rel_path| (rel_path, text.clone()))
+                })
+                .collect()
+        }
+
+        fn watch(
+            &self,
+            _filter: &RootFilter,
+            _sink: crossbeam_channel::Sender<WatchEvent>,
+        ) -> Option<Box<dyn std::any::Any + Send>> {
+            // No notion of an external writer in the fake backend; tests
+            // drive file changes directly through `Vfs`'s overlay methods or
+            // by editing `files` and calling `Vfs::notify_changed`.
+            None
+        }
+    }
+
+    fn new_vfs() -> (Vfs, VfsRoot) {
+        let (mut vfs, roots) = Vfs::with_file_system(
+            vec![PathBuf::from("/fake-vfs-test-root")],
+            RootConfig::default(),
+            Arc::new(FakeFileSystem::default()),
+        );
+        // drain the `BulkLoadRoot` the worker always sends for a freshly
+        // added root before the test touches any file.
+        let task = vfs.task_receiver().recv().unwrap();
+        vfs.handle_task(task);
+        vfs.commit_changes();
+        (vfs, roots[0])
+    }
+
+    #[test]
+    fn removed_file_ids_are_reused_before_allocating_new_ones() {
+        let (mut vfs, root) = new_vfs();
+        let path = vfs.root2path(root).unwrap().join("a.rs");
+
+        let file = vfs.add_file_overlay(&path, "fn a() {}".to_string()).unwrap();
+        vfs.commit_changes();
+
+        vfs.remove_file_overlay(&path);
+        vfs.commit_changes();
+
+        let reused = vfs.add_file_overlay(&path, "fn b() {}".to_string()).unwrap();
+
+        assert_eq!(
+            file, reused,
+            "a VfsFile freed by remove_file_overlay should be handed back out \
+             by the next add_file_overlay instead of allocating a fresh id"
+        );
+    }
+
+    #[test]
+    fn notify_changed_feeds_created_modified_removed_into_the_vfs() {
+        let fs = Arc::new(FakeFileSystem::default());
+        let (mut vfs, roots) =
+            Vfs::with_file_system(vec![PathBuf::from("/fake-vfs-test-root")], RootConfig::default(), fs.clone());
+        let root = roots[0];
+        let task = vfs.task_receiver().recv().unwrap();
+        vfs.handle_task(task);
+        vfs.commit_changes();
+
+        let path = vfs.root2path(root).unwrap().join("a.rs");
+
+        fs.files.lock().unwrap().insert(path.clone(), "fn a() {}".to_string());
+        vfs.notify_changed(&path, ChangeKind::Created);
+        match vfs.commit_changes().as_slice() {
+            [VfsChange::AddFile { path: got_path, text, .. }] => {
+                assert_eq!(*got_path, RelativePathBuf::from("a.rs"));
+                assert_eq!(text.as_str(), "fn a() {}");
+            }
+            other => panic!("expected a single AddFile change, got {:?}", other),
+        }
+
+        fs.files.lock().unwrap().insert(path.clone(), "fn b() {}".to_string());
+        vfs.notify_changed(&path, ChangeKind::Modified);
+        match vfs.commit_changes().as_slice() {
+            [VfsChange::ChangeFile { text, .. }] => assert_eq!(text.as_str(), "fn b() {}"),
+            other => panic!("expected a single ChangeFile change, got {:?}", other),
+        }
+
+        fs.files.lock().unwrap().remove(&path);
+        vfs.notify_changed(&path, ChangeKind::Removed);
+        match vfs.commit_changes().as_slice() {
+            [VfsChange::RemoveFile { .. }] => {}
+            other => panic!("expected a single RemoveFile change, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn with_file_system_bulk_loads_through_the_injected_backend() {
+        let fs = Arc::new(FakeFileSystem::default());
+        fs.files.lock().unwrap().insert(
+            PathBuf::from("/fake-vfs-test-root/lib.rs"),
+            "fn it_works() {}".to_string(),
+        );
+
+        let (mut vfs, roots) =
+            Vfs::with_file_system(vec![PathBuf::from("/fake-vfs-test-root")], RootConfig::default(), fs);
+        let root = roots[0];
+
+        let task = vfs.task_receiver().recv().unwrap();
+        vfs.handle_task(task);
+        let changes = vfs.commit_changes();
+
+        match changes.as_slice() {
+            [VfsChange::AddRoot { root: got_root, files }] => {
+                assert_eq!(*got_root, root);
+                assert_eq!(files.len(), 1);
+                assert_eq!(files[0].1, RelativePathBuf::from("lib.rs"));
+                assert_eq!(files[0].2.as_str(), "fn it_works() {}");
+            }
+            other => panic!("expected a single AddRoot change, got {:?}", other),
+        }
+    }
+}